@@ -1,8 +1,9 @@
 use aes_gcm::Aes256Gcm;
-use base64::encode;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{decode, encode};
 use chacha20poly1305::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
-    ChaCha20Poly1305, Error,
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Error, Key, Nonce, XChaCha20Poly1305, XNonce,
 };
 use chrono::NaiveDateTime;
 use clap::{Parser, Subcommand, ValueEnum};
@@ -10,9 +11,10 @@ use config::Config;
 use reqwest::blocking::Response;
 use reqwest::header::HeaderMap;
 use rpassword;
+use secrecy::{ExposeSecret, Secret, SecretVec};
 use serde::Deserialize;
 use std::fmt;
-use std::io::{self, BufRead};
+use std::io::{self, Read, Write};
 use std::time::Duration;
 use std::time::Instant;
 
@@ -21,6 +23,17 @@ use duration_human::{DurationHuman, DurationHumanValidator};
 
 assign_duration_range_validator!( EXPIRATION_RANGE = {default: 2h, min: 5min, max: 4day});
 
+/// Length in bytes of the nonce that AES-256-GCM and ChaCha20-Poly1305 prepend
+/// to the ciphertext (XChaCha20-Poly1305 uses 24 bytes, see `Cipher::nonce_len`).
+const NONCE_LEN: usize = 12;
+
+/// Argon2id parameters used when deriving a key from a passphrase.
+const ARGON2_MEMORY: u32 = 19456;
+const ARGON2_ITERATIONS: u32 = 2;
+const ARGON2_LANES: u32 = 1;
+const ARGON2_SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
 const URL_SAFE_ENGINE: base64::engine::fast_portable::FastPortable =
     base64::engine::fast_portable::FastPortable::from(
         &base64::alphabet::URL_SAFE,
@@ -33,6 +46,29 @@ struct CreateResponse {
     expires_at: i64,
 }
 
+#[derive(Deserialize, Debug)]
+struct GetResponse {
+    #[serde(rename = "encryptedBytes")]
+    encrypted_bytes: String,
+    cipher: String,
+    #[serde(default)]
+    kdf: Option<Kdf>,
+}
+
+/// Key-derivation parameters stored alongside the ciphertext when the secret
+/// is sealed with a passphrase. The share URL then carries no key, so the
+/// passphrase must be communicated out of band.
+#[derive(serde_derive::Serialize, Deserialize, Debug)]
+struct Kdf {
+    version: u32,
+    variant: String,
+    iterations: u32,
+    #[serde(rename = "memorySize")]
+    memory_size: u32,
+    lanes: u32,
+    salt: String,
+}
+
 #[derive(Parser, Debug)]
 #[command(
     author,
@@ -82,6 +118,42 @@ enum Commands {
             help = "Read from stdin. Useful for reading files through unix pipes"
         )]
         read_from_stdin: bool,
+
+        #[arg(
+            short = 'f',
+            long,
+            value_name = "PATH",
+            help = "Read the secret from a file instead of a prompt"
+        )]
+        file: Option<String>,
+
+        #[arg(
+            short,
+            long,
+            help = "Derive the key from a passphrase instead of embedding a random key in the URL"
+        )]
+        passphrase: bool,
+    },
+
+    /// Fetch and decrypt a secret from a neots share URL
+    Get {
+        /// The neots share URL; without `--passphrase` it must include the `#`-fragment key
+        url: String,
+
+        #[arg(
+            short,
+            long,
+            help = "Decrypt using a passphrase instead of the key from the URL"
+        )]
+        passphrase: bool,
+
+        #[arg(
+            short = 'o',
+            long,
+            value_name = "PATH",
+            help = "Write the decrypted secret to a file instead of stdout"
+        )]
+        output: Option<String>,
     },
 }
 
@@ -93,6 +165,28 @@ enum Cipher {
     Aes256gcm,
     ///  ChaCha20-Poly1305
     Chapoly,
+    /// XChaCha20-Poly1305 (extended 24-byte nonce)
+    Xchapoly,
+}
+
+impl Cipher {
+    /// Resolve the cipher from the `cipher` string reported by the backend.
+    fn from_name(name: &str) -> Option<Cipher> {
+        match name {
+            "aes256gcm" => Some(Cipher::Aes256gcm),
+            "chapoly" => Some(Cipher::Chapoly),
+            "xchapoly" => Some(Cipher::Xchapoly),
+            _ => None,
+        }
+    }
+
+    /// Length in bytes of the nonce this cipher prepends to the ciphertext.
+    fn nonce_len(&self) -> usize {
+        match self {
+            Cipher::Xchapoly => 24,
+            Cipher::Aes256gcm | Cipher::Chapoly => NONCE_LEN,
+        }
+    }
 }
 
 impl fmt::Display for Cipher {
@@ -100,6 +194,7 @@ impl fmt::Display for Cipher {
         match *self {
             Cipher::Aes256gcm => write!(f, "aes256gcm"),
             Cipher::Chapoly => write!(f, "chapoly"),
+            Cipher::Xchapoly => write!(f, "xchapoly"),
         }
     }
 }
@@ -118,22 +213,48 @@ fn main() {
             expiration,
             cipher,
             read_from_stdin,
-        } => new(expiration, cipher, read_from_stdin),
+            file,
+            passphrase,
+        } => new(expiration, cipher, read_from_stdin, file, passphrase),
+        Commands::Get {
+            url,
+            passphrase,
+            output,
+        } => get(url, passphrase, output),
     }
 }
 
-fn new(expiration: DurationHuman, cipher: Cipher, read_from_stdin: bool) {
+fn new(
+    expiration: DurationHuman,
+    cipher: Cipher,
+    read_from_stdin: bool,
+    file: Option<String>,
+    passphrase: bool,
+) {
     let duration = get_duration(expiration.into());
-    let secret = read_secret(read_from_stdin);
+    let secret = read_secret(read_from_stdin, file);
+
+    let (key, kdf) = if passphrase {
+        let kdf = new_kdf();
+        let key = derive_key(&read_passphrase(), &kdf);
+        (key, Some(kdf))
+    } else {
+        (generate_key(), None)
+    };
 
-    match encrypt(secret, cipher) {
-        (Ok(ciphertext), nonce, key) => {
+    match encrypt(secret, cipher, &key) {
+        (Ok(ciphertext), nonce) => {
             let ciphertext_with_nonce: Vec<u8> = [nonce, ciphertext].concat();
             let encoded = encode(&ciphertext_with_nonce);
-            let resp = send_to_backend(encoded, cipher, duration);
+            let resp = send_to_backend(encoded, cipher, duration, kdf.as_ref());
             let view_url = get_view_url(resp.headers());
             let json: CreateResponse = resp.json().unwrap();
-            let url = create_url(view_url, key);
+            // In passphrase mode the URL intentionally carries no key.
+            let url = if passphrase {
+                format!("{}?ref=neots", view_url)
+            } else {
+                create_url(view_url, key)
+            };
             let formatted = NaiveDateTime::from_timestamp_opt(json.expires_at, 0)
                 .unwrap()
                 .format("%Y-%m-%d %H:%M:%S");
@@ -152,37 +273,100 @@ automatically expire at approximately {expires_at} UTC",
             );
         }
 
-        (Err(err), _, _) => panic!("Could not encrypt secret: {:?}", err),
+        (Err(err), _) => panic!("Could not encrypt secret: {:?}", err),
+    };
+}
+
+fn get(url: String, passphrase: bool, output: Option<String>) {
+    let (fetch_url, encoded_key) = parse_share_url(&url);
+
+    let client = reqwest::blocking::Client::new();
+    // `fetch_url` is the view URL with its `?ref=neots` query and `#` fragment
+    // stripped; ask for JSON explicitly so the
+    // backend serves the `{encryptedBytes,cipher,kdf}` payload rather than the
+    // HTML viewer.
+    let body = client
+        .get(fetch_url)
+        .header(reqwest::header::ACCEPT, "application/json")
+        .send()
+        .unwrap()
+        .text()
+        .unwrap();
+    // The secret is deleted on first retrieval, so an empty body means it has
+    // already been viewed or the expiration has passed.
+    if body.is_empty() {
+        eprintln!("The secret has already been viewed or has expired.");
+        return;
+    }
+
+    let json: GetResponse = serde_json::from_str(&body).unwrap_or_else(|err| {
+        panic!("Expected JSON from the retrieval endpoint but got: {}\n{}", err, body)
+    });
+    let cipher = Cipher::from_name(&json.cipher)
+        .unwrap_or_else(|| panic!("Unknown cipher returned by server: {}", json.cipher));
+
+    // The `--passphrase` flag selects the mode: with it the key is re-derived
+    // from the stored KDF parameters, without it the key is the fragment of the
+    // share URL.
+    let key = if passphrase {
+        let kdf = json.kdf.as_ref().unwrap_or_else(|| {
+            panic!("--passphrase given but this secret was not created with a passphrase")
+        });
+        derive_key(&read_passphrase(), kdf)
+    } else {
+        if json.kdf.is_some() {
+            panic!("This secret was sealed with a passphrase; pass --passphrase to decrypt it");
+        }
+        let encoded_key = encoded_key.expect("Share URL must contain a `#` fragment with the key");
+        SecretVec::new(base64::decode_engine(encoded_key, &URL_SAFE_ENGINE).unwrap())
     };
+
+    let ciphertext_with_nonce = decode(json.encrypted_bytes).unwrap();
+    let (nonce, ciphertext) = ciphertext_with_nonce.split_at(cipher.nonce_len());
+
+    match decrypt(cipher, key.expose_secret(), nonce, ciphertext) {
+        Ok(plaintext) => write_secret(SecretVec::new(plaintext), output),
+        Err(err) => panic!("Could not decrypt secret: {:?}", err),
+    }
 }
 
-fn read_secret(read_from_stdin: bool) -> String {
-    if read_from_stdin {
-        read_stdin().unwrap()
+fn write_secret(plaintext: SecretVec<u8>, output: Option<String>) {
+    match output {
+        Some(path) => std::fs::write(path, plaintext.expose_secret()).unwrap(),
+        None => io::stdout().write_all(plaintext.expose_secret()).unwrap(),
+    }
+}
+
+fn parse_share_url(url: &str) -> (String, Option<String>) {
+    match url.split_once('#') {
+        Some((base, fragment)) => (
+            base.split('?').next().unwrap().to_string(),
+            Some(fragment.to_string()),
+        ),
+        None => (url.split('?').next().unwrap().to_string(), None),
+    }
+}
+
+fn read_secret(read_from_stdin: bool, file: Option<String>) -> SecretVec<u8> {
+    if let Some(path) = file {
+        SecretVec::new(std::fs::read(path).unwrap())
+    } else if read_from_stdin {
+        SecretVec::new(read_stdin().unwrap())
     } else {
         println!("");
-        rpassword::prompt_password("Enter your secret: ").unwrap()
+        let secret = rpassword::prompt_password("Enter your secret: ").unwrap();
+        SecretVec::new(secret.into_bytes())
     }
 }
 
-fn read_stdin() -> io::Result<String> {
-    let mut lines = io::stdin().lock().lines();
-    let mut user_input = String::new();
+fn read_passphrase() -> Secret<String> {
+    Secret::new(rpassword::prompt_password("Enter passphrase: ").unwrap())
+}
 
-    while let Some(line) = lines.next() {
-        let last_input = line.unwrap();
-        // stop reading
-        if last_input.len() == 0 {
-            break;
-        }
-        // add a new line once user_input starts storing user input
-        if user_input.len() > 0 {
-            user_input.push_str("\n");
-        }
-        // store user input
-        user_input.push_str(&last_input);
-    }
-    Ok(user_input)
+fn read_stdin() -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    io::stdin().lock().read_to_end(&mut buf)?;
+    Ok(buf)
 }
 
 fn app_config() -> AppConfig {
@@ -206,41 +390,120 @@ fn get_duration(expiration: DurationHuman) -> Duration {
     then - now
 }
 
-type EncryptionResult = (Result<Vec<u8>, Error>, Vec<u8>, Vec<u8>);
+type EncryptionResult = (Result<Vec<u8>, Error>, Vec<u8>);
 
-fn encrypt(secret: String, cipher: Cipher) -> EncryptionResult {
+/// Generate a fresh random AEAD key.
+fn generate_key() -> SecretVec<u8> {
+    SecretVec::new(ChaCha20Poly1305::generate_key(&mut OsRng).to_vec())
+}
+
+/// Build the KDF parameters for a new passphrase-sealed secret, with a fresh
+/// random salt.
+fn new_kdf() -> Kdf {
+    let mut salt = [0u8; ARGON2_SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    Kdf {
+        version: Version::V0x13 as u32,
+        variant: "argon2id".to_string(),
+        iterations: ARGON2_ITERATIONS,
+        memory_size: ARGON2_MEMORY,
+        lanes: ARGON2_LANES,
+        salt: base64::encode_engine(salt, &URL_SAFE_ENGINE),
+    }
+}
+
+/// Derive a 32-byte AEAD key from a passphrase using the stored Argon2
+/// parameters (variant, version, costs and salt).
+fn derive_key(passphrase: &Secret<String>, kdf: &Kdf) -> SecretVec<u8> {
+    let salt = base64::decode_engine(&kdf.salt, &URL_SAFE_ENGINE).unwrap();
+    let params = Params::new(kdf.memory_size, kdf.iterations, kdf.lanes, Some(KEY_LEN)).unwrap();
+    // Honour the stored variant/version so a secret sealed with other Argon2
+    // settings derives the same key; an unknown variant or version fails with a
+    // clear message rather than a generic AEAD error from a silently wrong key.
+    let variant = match kdf.variant.as_str() {
+        "argon2id" => Algorithm::Argon2id,
+        "argon2i" => Algorithm::Argon2i,
+        "argon2d" => Algorithm::Argon2d,
+        other => panic!("Unknown Argon2 variant in stored KDF parameters: {}", other),
+    };
+    let version = match kdf.version {
+        0x10 => Version::V0x10,
+        0x13 => Version::V0x13,
+        other => panic!("Unknown Argon2 version in stored KDF parameters: {:#x}", other),
+    };
+    let argon2 = Argon2::new(variant, version, params);
+    let mut key = vec![0u8; KEY_LEN];
+    argon2
+        .hash_password_into(passphrase.expose_secret().as_bytes(), &salt, &mut key)
+        .unwrap();
+    SecretVec::new(key)
+}
+
+fn encrypt(secret: SecretVec<u8>, cipher: Cipher, key: &SecretVec<u8>) -> EncryptionResult {
     match cipher {
-        Cipher::Chapoly => encrypt_chapoly(secret),
-        Cipher::Aes256gcm => encrypt_aes(secret),
+        Cipher::Chapoly => encrypt_chapoly(secret, key),
+        Cipher::Aes256gcm => encrypt_aes(secret, key),
+        Cipher::Xchapoly => encrypt_xchapoly(secret, key),
     }
 }
 
-fn encrypt_chapoly(secret: String) -> EncryptionResult {
-    let key = ChaCha20Poly1305::generate_key(&mut OsRng);
-    let cipher = ChaCha20Poly1305::new(&key);
+fn decrypt(cipher: Cipher, key: &[u8], nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+    match cipher {
+        Cipher::Chapoly => {
+            let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+            cipher.decrypt(Nonce::from_slice(nonce), ciphertext)
+        }
+        Cipher::Aes256gcm => {
+            let cipher = Aes256Gcm::new(aes_gcm::Key::<Aes256Gcm>::from_slice(key));
+            cipher.decrypt(aes_gcm::Nonce::from_slice(nonce), ciphertext)
+        }
+        Cipher::Xchapoly => {
+            let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+            cipher.decrypt(XNonce::from_slice(nonce), ciphertext)
+        }
+    }
+}
+
+fn encrypt_chapoly(secret: SecretVec<u8>, key: &SecretVec<u8>) -> EncryptionResult {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key.expose_secret()));
     let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
-    let ciphertext = cipher.encrypt(&nonce, secret.as_ref());
-    (ciphertext, nonce.to_vec(), key.to_vec())
+    let ciphertext = cipher.encrypt(&nonce, secret.expose_secret().as_slice());
+    (ciphertext, nonce.to_vec())
 }
 
-fn encrypt_aes(secret: String) -> EncryptionResult {
-    let key = Aes256Gcm::generate_key(&mut OsRng);
-    let cipher = Aes256Gcm::new(&key);
+fn encrypt_aes(secret: SecretVec<u8>, key: &SecretVec<u8>) -> EncryptionResult {
+    let cipher = Aes256Gcm::new(aes_gcm::Key::<Aes256Gcm>::from_slice(key.expose_secret()));
     let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
-    let ciphertext = cipher.encrypt(&nonce, secret.as_ref());
-    (ciphertext, nonce.to_vec(), key.to_vec())
+    let ciphertext = cipher.encrypt(&nonce, secret.expose_secret().as_slice());
+    (ciphertext, nonce.to_vec())
 }
 
-fn send_to_backend(encrypted_secret: String, cipher: Cipher, expiration: Duration) -> Response {
+fn encrypt_xchapoly(secret: SecretVec<u8>, key: &SecretVec<u8>) -> EncryptionResult {
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key.expose_secret()));
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, secret.expose_secret().as_slice());
+    (ciphertext, nonce.to_vec())
+}
+
+fn send_to_backend(
+    encrypted_secret: String,
+    cipher: Cipher,
+    expiration: Duration,
+    kdf: Option<&Kdf>,
+) -> Response {
     let app_config = app_config();
     let client = reqwest::blocking::Client::new();
+    let mut payload = serde_json::json!({
+        "encryptedBytes": encrypted_secret,
+        "expiresIn": expiration.as_secs(),
+        "cipher": cipher.to_string()
+    });
+    if let Some(kdf) = kdf {
+        payload["kdf"] = serde_json::to_value(kdf).unwrap();
+    }
     client
         .post(app_config.api_url)
-        .json(&serde_json::json!({
-            "encryptedBytes": encrypted_secret,
-            "expiresIn": expiration.as_secs(),
-            "cipher": cipher.to_string()
-        }))
+        .json(&payload)
         .send()
         .unwrap()
 }
@@ -254,7 +517,7 @@ fn get_view_url(headers: &HeaderMap) -> String {
         .to_string()
 }
 
-fn create_url(view_url: String, key: Vec<u8>) -> String {
-    let encoded_key = base64::encode_engine(key, &URL_SAFE_ENGINE);
+fn create_url(view_url: String, key: SecretVec<u8>) -> String {
+    let encoded_key = base64::encode_engine(key.expose_secret(), &URL_SAFE_ENGINE);
     format!("{}?ref=neots#{}", view_url, encoded_key)
 }